@@ -7,53 +7,272 @@
 //! 3. 监听进度上报 (+QIND: "FOTA","UPDATING",进度)
 //! 4. 等待升级完成 (+QIND: "FOTA","END",0)
 //!
-//! 依赖: cargo add serialport regex chrono
+//! 依赖: cargo add serialport regex chrono ureq md5 ed25519-dalek hmac sha2
 
 use chrono::Local;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
 use regex::Regex;
 use serialport::{available_ports, SerialPort};
+use sha2::Sha256;
 use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
 const DEFAULT_BAUDRATE: u32 = 115200;
 const AT_TIMEOUT: Duration = Duration::from_secs(2);
 
+/// 共享串口句柄：命令收发和 URC 监听线程共用同一个端口
+type SharedPort = Arc<Mutex<Option<Box<dyn SerialPort>>>>;
+
+/// 清空串口 RX 缓冲：用很短的超时反复读，直到读不到数据为止。
+/// 调用方需已持有端口锁（命令在途），避免与监听线程争抢。
+fn drain_rx(port: &mut Box<dyn SerialPort>) {
+    let mut buf = [0u8; 256];
+    let _ = port.set_timeout(Duration::from_millis(30));
+    loop {
+        match port.read(&mut buf) {
+            Ok(n) if n > 0 => continue,
+            _ => break,
+        }
+    }
+    let _ = port.set_timeout(AT_TIMEOUT);
+}
+
 /// 带时间戳的日志
 fn log(msg: &str) {
     let timestamp = Local::now().format("%H:%M:%S%.3f");
     println!("[{}] {}", timestamp, msg);
 }
 
+/// 进度停滞判定窗口：下载阶段超过该时间没有新的进度上报即视为卡死。
+/// 仅在下载阶段生效——HTTPEND 之后的烧写阶段本就不上报进度。
+const FOTA_IDLE_WINDOW: Duration = Duration::from_secs(60);
+
 /// FOTA状态
 struct FotaState {
     complete: bool,
     result: i32,
+    progress: i32,
+    /// 本轮升级开始时刻，用于推算 ETA
+    started_at: Instant,
+    /// 最近一次进度/下载上报时刻，用于停滞检测
+    last_update: Instant,
+    /// HTTPEND 网络错误码：非 None 表示下载中断、可尝试断点续传
+    http_error: Option<i32>,
+    /// 是否仍在下载阶段：HTTPEND 成功后转入烧写阶段，停滞检测随之关闭
+    downloading: bool,
+}
+
+/// wait_for_fota_complete 的等待结果
+enum FotaWait {
+    /// 收到 END，携带结果码（0=成功）
+    Done(i32),
+    /// HTTPEND 网络错误，可重发 AT+QFOTADL 续传
+    DownloadError(i32),
+    /// 长时间无进度上报，判定为卡死
+    Stall,
+    /// 达到整体超时
+    Timeout,
+}
+
+/// MQTT 遥测上报器：全程走模组自带的 AT+QMTOPEN/QMTCONN/QMTPUB 栈，
+/// 让车队后台可以实时观察升级过程。
+#[derive(Clone)]
+struct MqttReporter {
+    host: String,
+    port: u16,
+    client_id: String,
+    base_topic: String,
+    client_idx: u8,
+    connected: bool,
+}
+
+impl MqttReporter {
+    /// 解析 mqtt://host:port/base/topic 形式的 URI
+    fn from_uri(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .strip_prefix("mqtt://")
+            .ok_or_else(|| "MQTT URI 需以 mqtt:// 开头".to_string())?;
+        let (authority, topic) = match rest.split_once('/') {
+            Some((a, t)) => (a, t),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((h, p)) => (
+                h.to_string(),
+                p.parse::<u16>().map_err(|_| format!("端口号无效: {}", p))?,
+            ),
+            None => (authority.to_string(), 1883),
+        };
+        if host.is_empty() {
+            return Err("MQTT 主机名为空".to_string());
+        }
+        Ok(MqttReporter {
+            host,
+            port,
+            client_id: "ec800k-fota".to_string(),
+            base_topic: if topic.is_empty() {
+                "fota".to_string()
+            } else {
+                topic.trim_end_matches('/').to_string()
+            },
+            client_idx: 0,
+            connected: false,
+        })
+    }
+
+    /// 拼接完整主题: base_topic/sub
+    fn topic(&self, sub: &str) -> String {
+        format!("{}/{}", self.base_topic, sub)
+    }
+}
+
+/// 升级包预校验配置：在把 URL 丢给 AT+QFOTADL 之前，本地抓包并校验，
+/// 提前拦住 505/506/507/552/553 这类错误，免得模组白跑一次下载。
+struct VerifyConfig {
+    /// 期望的 MD5 摘要（十六进制，来自命令行）
+    expected_md5: Option<String>,
+    /// 根密钥文件：Ed25519 公钥(32字节原始) 或 HMAC 密钥
+    key_file: Option<String>,
+    /// 签名文件：Ed25519 为 64 字节原始签名，HMAC 为 32 字节摘要
+    sig_file: Option<String>,
+    /// 签名算法：显式指定(--sig-alg)，不按密钥长度猜测；提供密钥时必填
+    sig_alg: Option<SigAlg>,
+}
+
+/// 签名算法选择（由 --sig-alg 显式给定）
+#[derive(Clone, Copy, PartialEq)]
+enum SigAlg {
+    Ed25519,
+    Hmac,
+}
+
+impl SigAlg {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "ed25519" => Ok(SigAlg::Ed25519),
+            "hmac" => Ok(SigAlg::Hmac),
+            other => Err(format!("未知签名算法: {} (可选 ed25519|hmac)", other)),
+        }
+    }
+}
+
+impl VerifyConfig {
+    /// 是否配置了任意一项校验
+    fn is_active(&self) -> bool {
+        self.expected_md5.is_some() || self.key_file.is_some()
+    }
+}
+
+/// 抓取待校验的升级包：http(s) 走 ureq，其余按本地文件路径读取。
+fn fetch_package(path_or_url: &str) -> Result<Vec<u8>, String> {
+    if path_or_url.starts_with("http://") || path_or_url.starts_with("https://") {
+        let resp = ureq::get(path_or_url)
+            .call()
+            .map_err(|e| format!("下载失败: {}", e))?;
+        let mut buf = Vec::new();
+        resp.into_reader()
+            .read_to_end(&mut buf)
+            .map_err(|e| format!("读取响应失败: {}", e))?;
+        Ok(buf)
+    } else {
+        fs::read(path_or_url).map_err(|e| format!("读取文件失败: {}", e))
+    }
+}
+
+/// GNSS 定位结果（由 +QGPSLOC 解析而来，经纬度已转为带符号的十进制度）
+#[derive(Clone)]
+struct GnssFix {
+    lat: f64,
+    lon: f64,
+    alt: f64,
+    sats: i32,
+    hdop: f64,
+    utc: String,
+}
+
+/// 把 ddmm.mmmm(N/S) / dddmm.mmmm(E/W) 形式转为带符号十进制度。
+/// deg_digits 表示整度部分占用的位数（纬度2位、经度3位）。
+fn nmea_to_decimal(raw: &str, deg_digits: usize) -> Option<f64> {
+    let raw = raw.trim();
+    if raw.len() <= deg_digits {
+        return None;
+    }
+    let (body, hemi) = match raw.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => (&raw[..raw.len() - 1], c.to_ascii_uppercase()),
+        _ => (raw, 'N'),
+    };
+    let deg: f64 = body.get(..deg_digits)?.parse().ok()?;
+    let min: f64 = body.get(deg_digits..)?.parse().ok()?;
+    let mut value = deg + min / 60.0;
+    if hemi == 'S' || hemi == 'W' {
+        value = -value;
+    }
+    Some(value)
 }
 
 /// EC800K 模块控制结构
 struct EC800KModem {
-    port: Option<Box<dyn SerialPort>>,
+    port: SharedPort,
     port_path: String,
     baud_rate: u32,
-    stop_monitor: Arc<Mutex<bool>>,
-    fota_state: Arc<Mutex<FotaState>>,
+    stop_monitor: Arc<AtomicBool>,
+    /// 命令在途标志：置位时监听线程让出串口，避免与 send_at_command 争抢 read
+    cmd_in_flight: Arc<AtomicBool>,
+    /// 监听线程是否在运行：运行期间不在命令前清空 RX，以免吞掉 FOTA URC
+    monitor_active: Arc<AtomicBool>,
+    /// FOTA状态 + 条件变量，监听线程更新后立即唤醒 wait_for_fota_complete
+    fota_state: Arc<(Mutex<FotaState>, Condvar)>,
+    monitor_handle: Option<JoinHandle<()>>,
+    /// 可选 MQTT 遥测上报器（--mqtt 开启）
+    mqtt: Option<MqttReporter>,
+    /// 最近一次 GNSS 定位结果
+    last_fix: Option<GnssFix>,
+    /// 可选的升级包预校验配置
+    verify: Option<VerifyConfig>,
+    /// 单条 AT 命令响应异常时的重发次数
+    max_retries: u32,
+    /// FOTA 以非零错误码结束时，复位并整体重试的次数
+    fota_retries: u32,
+    /// HTTPEND 网络错误时，重发 AT+QFOTADL 续传的次数上限
+    download_retries: u32,
 }
 
 impl EC800KModem {
     fn new(port_path: &str, baud_rate: u32) -> Self {
         EC800KModem {
-            port: None,
+            port: Arc::new(Mutex::new(None)),
             port_path: port_path.to_string(),
             baud_rate,
-            stop_monitor: Arc::new(Mutex::new(false)),
-            fota_state: Arc::new(Mutex::new(FotaState {
-                complete: false,
-                result: -1,
-            })),
+            stop_monitor: Arc::new(AtomicBool::new(false)),
+            cmd_in_flight: Arc::new(AtomicBool::new(false)),
+            monitor_active: Arc::new(AtomicBool::new(false)),
+            fota_state: Arc::new((
+                Mutex::new(FotaState {
+                    complete: false,
+                    result: -1,
+                    progress: 0,
+                    started_at: Instant::now(),
+                    last_update: Instant::now(),
+                    http_error: None,
+                    downloading: true,
+                }),
+                Condvar::new(),
+            )),
+            monitor_handle: None,
+            mqtt: None,
+            last_fix: None,
+            verify: None,
+            max_retries: 2,
+            fota_retries: 0,
+            download_retries: 2,
         }
     }
 
@@ -63,7 +282,7 @@ impl EC800KModem {
             .open()
         {
             Ok(port) => {
-                self.port = Some(port);
+                *self.port.lock().unwrap() = Some(port);
                 log(&format!(
                     "✅ 串口连接成功: {} @ {}bps",
                     self.port_path, self.baud_rate
@@ -75,9 +294,11 @@ impl EC800KModem {
     }
 
     fn disconnect(&mut self) {
-        *self.stop_monitor.lock().unwrap() = true;
-        if self.port.is_some() {
-            self.port = None;
+        self.stop_monitor.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.monitor_handle.take() {
+            let _ = handle.join();
+        }
+        if self.port.lock().unwrap().take().is_some() {
             log("🔌 串口已断开");
         }
     }
@@ -85,43 +306,415 @@ impl EC800KModem {
     fn send_at_command(&mut self, cmd: &str, timeout: Duration) -> (bool, String) {
         log(&format!("📤 发送: {}", cmd));
 
-        let port = match &mut self.port {
-            Some(p) => p,
-            None => return (false, "串口未连接".to_string()),
+        // 暂停监听线程，确保同步命令/响应与异步 URC 流不会并发 read
+        self.cmd_in_flight.store(true, Ordering::SeqCst);
+        let monitor_running = self.monitor_active.load(Ordering::SeqCst);
+
+        let mut attempt = 0;
+        let last = loop {
+            let result = {
+                let mut guard = self.port.lock().unwrap();
+                let port = match guard.as_mut() {
+                    Some(p) => p,
+                    None => {
+                        self.cmd_in_flight.store(false, Ordering::SeqCst);
+                        return (false, "串口未连接".to_string());
+                    }
+                };
+
+                // 发命令前清空 RX 缓冲，避免残留 URC 污染本次响应。
+                // 监听线程运行时跳过：此刻缓冲里的字节可能是 FOTA URC，随响应一起读出后回喂 URC 解析。
+                if !monitor_running {
+                    drain_rx(port);
+                }
+
+                // 发送命令
+                let cmd_bytes = format!("{}\r\n", cmd);
+                if let Err(e) = port.write_all(cmd_bytes.as_bytes()) {
+                    drop(guard);
+                    self.cmd_in_flight.store(false, Ordering::SeqCst);
+                    return (false, format!("发送失败: {}", e));
+                }
+
+                // 读取响应
+                let mut response = String::new();
+                let mut buf = [0u8; 256];
+                let start = Instant::now();
+
+                while start.elapsed() < timeout {
+                    match port.read(&mut buf) {
+                        Ok(n) if n > 0 => {
+                            response.push_str(&String::from_utf8_lossy(&buf[..n]));
+                            if response.contains("OK") || response.contains("ERROR") {
+                                break;
+                            }
+                        }
+                        _ => {
+                            thread::sleep(Duration::from_millis(50));
+                        }
+                    }
+                }
+                response
+            };
+
+            let candidate = result.trim().to_string();
+
+            // 响应为空或既无 OK 也无 ERROR 时视为丢失，重发
+            let usable =
+                !candidate.is_empty() && (candidate.contains("OK") || candidate.contains("ERROR"));
+            if usable || attempt >= self.max_retries {
+                break candidate;
+            }
+            attempt += 1;
+            log(&format!(
+                "🔁 响应异常，重试 {}/{}: {}",
+                attempt, self.max_retries, cmd
+            ));
+            thread::sleep(Duration::from_millis(200));
         };
 
-        // 发送命令
-        let cmd_bytes = format!("{}\r\n", cmd);
-        if let Err(e) = port.write_all(cmd_bytes.as_bytes()) {
-            return (false, format!("发送失败: {}", e));
+        self.cmd_in_flight.store(false, Ordering::SeqCst);
+
+        if !last.is_empty() {
+            log(&format!("📥 响应: {}", last));
+        }
+
+        // 监听线程在途时让出了串口，本次读循环可能顺带收到并发到达的 FOTA URC，
+        // 而响应扫描只认 OK/ERROR。把整段响应回喂 URC 解析，避免续传命令窗口里丢掉
+        // HTTPEND/END（否则升级实际成功却被判为超时）。
+        if monitor_running {
+            self.feed_fota_urc(&last);
+        }
+
+        let success = last.contains("OK");
+        (success, last)
+    }
+
+    /// 把命令响应里夹带的 FOTA 终态 URC（HTTPEND/END）回喂状态机。
+    /// 仅在命令占用串口、监听线程让出期间调用，补上监听线程看不到的那段字节。
+    fn feed_fota_urc(&self, text: &str) {
+        let re_httpend = Regex::new(r#"\+QIND:\s*"FOTA","HTTPEND",(-?\d+)"#).unwrap();
+        let re_end = Regex::new(r#"\+QIND:\s*"FOTA","END",(-?\d+)"#).unwrap();
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(caps) = re_end.captures(line) {
+                let err: i32 = caps[1].parse().unwrap_or(-1);
+                let (lock, cvar) = &*self.fota_state;
+                let mut st = lock.lock().unwrap();
+                st.complete = true;
+                st.result = err;
+                cvar.notify_all();
+                drop(st);
+                log(&format!("🏁 升级结束 (END)，错误码: {}", err));
+            } else if let Some(caps) = re_httpend.captures(line) {
+                let err: i32 = caps[1].parse().unwrap_or(-1);
+                let (lock, cvar) = &*self.fota_state;
+                let mut st = lock.lock().unwrap();
+                st.last_update = Instant::now();
+                if err != 0 {
+                    st.http_error = Some(err);
+                    cvar.notify_all();
+                    drop(st);
+                    log(&format!("❌ HTTP下载失败，错误码: {}", err));
+                } else {
+                    st.downloading = false;
+                    cvar.notify_all();
+                    drop(st);
+                    log("✅ HTTP下载完成 (HTTPEND)");
+                }
+            }
         }
+    }
+
+    /// 复位 routine：下发 AT+CFUN=1,1 软复位，循环 test_at 直到模组重新应答。
+    fn recover(&mut self) -> bool {
+        log("🛠️  尝试复位模组 (AT+CFUN=1,1)...");
+        self.send_at_command("AT+CFUN=1,1", AT_TIMEOUT);
 
-        // 读取响应
-        let mut response = String::new();
-        let mut buf = [0u8; 256];
         let start = Instant::now();
+        let deadline = Duration::from_secs(60);
+        while start.elapsed() < deadline {
+            thread::sleep(Duration::from_secs(3));
+            if self.test_at() {
+                log("✅ 模组已恢复应答");
+                return true;
+            }
+        }
+        log("❌ 模组复位后仍无应答");
+        false
+    }
 
-        while start.elapsed() < timeout {
-            match port.read(&mut buf) {
-                Ok(n) if n > 0 => {
-                    response.push_str(&String::from_utf8_lossy(&buf[..n]));
-                    if response.contains("OK") || response.contains("ERROR") {
-                        break;
-                    }
+    /// 发送原始数据并读取响应，直到命中任一 marker 或超时。
+    /// 用于 QMTOPEN/QMTCONN 这类需要等待异步结果 URC、以及需要 `>` 提示符的场景。
+    fn send_and_wait(&mut self, data: &str, markers: &[&str], timeout: Duration) -> (bool, String) {
+        self.cmd_in_flight.store(true, Ordering::SeqCst);
+        let response = {
+            let mut guard = self.port.lock().unwrap();
+            let port = match guard.as_mut() {
+                Some(p) => p,
+                None => {
+                    self.cmd_in_flight.store(false, Ordering::SeqCst);
+                    return (false, "串口未连接".to_string());
                 }
-                _ => {
-                    thread::sleep(Duration::from_millis(50));
+            };
+            if let Err(e) = port.write_all(data.as_bytes()) {
+                drop(guard);
+                self.cmd_in_flight.store(false, Ordering::SeqCst);
+                return (false, format!("发送失败: {}", e));
+            }
+
+            let mut response = String::new();
+            let mut buf = [0u8; 256];
+            let start = Instant::now();
+            while start.elapsed() < timeout {
+                match port.read(&mut buf) {
+                    Ok(n) if n > 0 => {
+                        response.push_str(&String::from_utf8_lossy(&buf[..n]));
+                        if markers.iter().any(|m| response.contains(m))
+                            || response.contains("ERROR")
+                        {
+                            break;
+                        }
+                    }
+                    _ => thread::sleep(Duration::from_millis(50)),
                 }
             }
+            response
+        };
+        self.cmd_in_flight.store(false, Ordering::SeqCst);
+        let hit = markers.iter().any(|m| response.contains(m));
+        (hit, response.trim().to_string())
+    }
+
+    /// 打开并连接 MQTT 客户端，随后发布 {imei, firmware_version} 连接消息。
+    fn mqtt_connect(&mut self) -> bool {
+        let reporter = match &self.mqtt {
+            Some(r) => r.clone(),
+            None => return false,
+        };
+
+        log("\n[MQTT] 打开客户端...");
+        let open_cmd = format!(
+            "AT+QMTOPEN={},\"{}\",{}\r\n",
+            reporter.client_idx, reporter.host, reporter.port
+        );
+        let (ok, resp) = self.send_and_wait(&open_cmd, &["+QMTOPEN:"], Duration::from_secs(15));
+        // +QMTOPEN: <idx>,<result>  result 0=成功(或已打开)
+        let open_ok = ok
+            && Regex::new(r"\+QMTOPEN:\s*\d+,\s*(-?\d+)")
+                .unwrap()
+                .captures(&resp)
+                .and_then(|c| c[1].parse::<i32>().ok())
+                .map(|r| r == 0)
+                .unwrap_or(false);
+        if !open_ok {
+            log(&format!("⚠️  MQTT QMTOPEN 失败: {}", resp));
+            return false;
+        }
+
+        log("[MQTT] 连接 Broker...");
+        let conn_cmd = format!(
+            "AT+QMTCONN={},\"{}\"\r\n",
+            reporter.client_idx, reporter.client_id
+        );
+        let (ok, resp) = self.send_and_wait(&conn_cmd, &["+QMTCONN:"], Duration::from_secs(15));
+        // +QMTCONN: <idx>,<result>,<ret_code>  result 0 且 ret_code 0 表示连接成功
+        let conn_ok = ok
+            && Regex::new(r"\+QMTCONN:\s*\d+,\s*0,\s*0")
+                .unwrap()
+                .is_match(&resp);
+        if !conn_ok {
+            log(&format!("⚠️  MQTT QMTCONN 失败: {}", resp));
+            return false;
+        }
+
+        if let Some(r) = self.mqtt.as_mut() {
+            r.connected = true;
+        }
+        log("✅ MQTT 已连接");
+
+        // 连接消息: {imei, firmware_version}
+        let imei = self
+            .get_module_info()
+            .get("imei")
+            .cloned()
+            .unwrap_or_default();
+        let fw = self.get_firmware_version();
+        let payload = format!(
+            "{{\"imei\":\"{}\",\"firmware_version\":\"{}\"}}",
+            imei, fw
+        );
+        self.mqtt_publish("status", &payload);
+        true
+    }
+
+    /// 发布一条保留(retained)消息并解析 +QMTPUB 结果确认投递。
+    fn mqtt_publish(&mut self, sub: &str, payload: &str) -> bool {
+        let (client_idx, topic) = match &self.mqtt {
+            Some(r) if r.connected => (r.client_idx, r.topic(sub)),
+            _ => return false,
+        };
+
+        // AT+QMTPUB=<client>,<msgid>,<qos>,<retain>,"topic"  → 等待 `>` 提示符
+        let cmd = format!("AT+QMTPUB={},0,0,1,\"{}\"\r\n", client_idx, topic);
+        let (got_prompt, _) = self.send_and_wait(&cmd, &[">"], Duration::from_secs(5));
+        if !got_prompt {
+            log("⚠️  MQTT 未收到发布提示符");
+            return false;
         }
 
-        let response = response.trim().to_string();
-        if !response.is_empty() {
-            log(&format!("📥 响应: {}", response));
+        // 负载 + Ctrl-Z(0x1A) 结束，等待 +QMTPUB: <client>,<msgid>,<result>
+        let body = format!("{}\x1a", payload);
+        let (ok, resp) = self.send_and_wait(&body, &["+QMTPUB:"], Duration::from_secs(10));
+        let pub_ok = ok
+            && Regex::new(r"\+QMTPUB:\s*\d+,\s*\d+,\s*(-?\d+)")
+                .unwrap()
+                .captures(&resp)
+                .and_then(|c| c[1].parse::<i32>().ok())
+                .map(|r| r == 0)
+                .unwrap_or(false);
+        if pub_ok {
+            log(&format!("📡 MQTT 已发布 [{}]: {}", sub, payload));
+        } else {
+            log(&format!("⚠️  MQTT 发布失败 [{}]: {}", sub, resp));
         }
+        pub_ok
+    }
+
+    /// 启动后台 URC 监听线程：循环读取串口、按 \r\n 拆分、匹配 +QIND FOTA 上报，
+    /// 把进度/结果写入 fota_state 并通过 Condvar 唤醒等待方。
+    fn start_fota_monitor(&mut self) {
+        let port = Arc::clone(&self.port);
+        let stop = Arc::clone(&self.stop_monitor);
+        let cmd_in_flight = Arc::clone(&self.cmd_in_flight);
+        let monitor_active = Arc::clone(&self.monitor_active);
+        let state = Arc::clone(&self.fota_state);
+        let mqtt = self.mqtt.clone().filter(|r| r.connected);
+
+        self.monitor_active.store(true, Ordering::SeqCst);
+
+        let re_updating = Regex::new(r#"\+QIND:\s*"FOTA","UPDATING",(\d+)"#).unwrap();
+        let re_httpend = Regex::new(r#"\+QIND:\s*"FOTA","HTTPEND",(-?\d+)"#).unwrap();
+        let re_end = Regex::new(r#"\+QIND:\s*"FOTA","END",(-?\d+)"#).unwrap();
+        let re_httpstart = Regex::new(r#"\+QIND:\s*"FOTA","HTTPSTART""#).unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut buffer = String::new();
+            let mut buf = [0u8; 256];
+            // 进度发布的待发负载：命令已写出、正在等待 `>` 提示符
+            let mut pending_pub: Option<String> = None;
+
+            while !stop.load(Ordering::SeqCst) {
+                // 命令在途时让出串口，交给 send_at_command 独占
+                if cmd_in_flight.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(20));
+                    continue;
+                }
+
+                let n = {
+                    let mut guard = port.lock().unwrap();
+                    match guard.as_mut() {
+                        Some(p) => p.read(&mut buf).unwrap_or(0),
+                        None => 0,
+                    }
+                };
+
+                if n == 0 {
+                    thread::sleep(Duration::from_millis(20));
+                    continue;
+                }
+
+                buffer.push_str(&String::from_utf8_lossy(&buf[..n]));
+
+                // 收到 QMTPUB 的 `>` 提示符后补发负载；其余字节仍留在 buffer 里按 URC 解析，
+                // 保证所有 read 只由监听线程完成，不会吞掉并发到达的 FOTA URC。
+                if pending_pub.is_some() {
+                    if let Some(gpos) = buffer.find('>') {
+                        if let Some(body) = pending_pub.take() {
+                            if let Some(p) = port.lock().unwrap().as_mut() {
+                                let _ = p.write_all(body.as_bytes());
+                            }
+                        }
+                        buffer.drain(..gpos + 1);
+                    }
+                }
+
+                // 按行处理，保留未完成的尾部
+                while let Some(pos) = buffer.find("\r\n") {
+                    let line: String = buffer.drain(..pos + 2).collect();
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
 
-        let success = response.contains("OK");
-        (success, response)
+                    if re_httpstart.is_match(line) {
+                        log("⬇️  FOTA下载开始 (HTTPSTART)");
+                    } else if let Some(caps) = re_updating.captures(line) {
+                        let pct: i32 = caps[1].parse().unwrap_or(0);
+                        let (lock, cvar) = &*state;
+                        let mut st = lock.lock().unwrap();
+                        st.progress = pct;
+                        st.last_update = Instant::now();
+                        // 按进度速率推算剩余时间
+                        let eta = if pct > 0 && pct < 100 {
+                            let elapsed = st.started_at.elapsed().as_secs_f64();
+                            let total = elapsed / pct as f64 * 100.0;
+                            format!(" (ETA~{}s)", (total - elapsed).max(0.0) as i64)
+                        } else {
+                            String::new()
+                        };
+                        cvar.notify_all();
+                        drop(st);
+                        log(&format!("📊 升级进度: {}%{}", pct, eta));
+                        // 进度遥测 QoS0 即发即走：只写出发布命令，负载交由读循环在看到
+                        // `>` 后补发，避免另起一个抢串口的 read 循环丢掉 FOTA URC。
+                        // 上一条还没补发负载时跳过本次，不做命令叠加。
+                        if let (Some(r), None) = (&mqtt, &pending_pub) {
+                            let cmd = format!(
+                                "AT+QMTPUB={},0,0,1,\"{}\"\r\n",
+                                r.client_idx,
+                                r.topic("progress")
+                            );
+                            if let Some(p) = port.lock().unwrap().as_mut() {
+                                if p.write_all(cmd.as_bytes()).is_ok() {
+                                    pending_pub = Some(format!("{{\"progress\":{}}}\x1a", pct));
+                                }
+                            }
+                        }
+                    } else if let Some(caps) = re_httpend.captures(line) {
+                        let err: i32 = caps[1].parse().unwrap_or(-1);
+                        let (lock, cvar) = &*state;
+                        let mut st = lock.lock().unwrap();
+                        st.last_update = Instant::now();
+                        // 下载阶段出错记为可续传的网络错误（正常结束后继续烧写并上报 END）
+                        if err != 0 {
+                            st.http_error = Some(err);
+                            cvar.notify_all();
+                            drop(st);
+                            log(&format!("❌ HTTP下载失败，错误码: {}", err));
+                        } else {
+                            // 下载结束，转入烧写阶段：烧写期间本就无进度上报，关闭停滞检测
+                            st.downloading = false;
+                            cvar.notify_all();
+                            drop(st);
+                            log("✅ HTTP下载完成 (HTTPEND)");
+                        }
+                    } else if let Some(caps) = re_end.captures(line) {
+                        let err: i32 = caps[1].parse().unwrap_or(-1);
+                        let (lock, cvar) = &*state;
+                        let mut st = lock.lock().unwrap();
+                        st.complete = true;
+                        st.result = err;
+                        cvar.notify_all();
+                        drop(st);
+                        log(&format!("🏁 升级结束 (END)，错误码: {}", err));
+                    }
+                }
+            }
+            monitor_active.store(false, Ordering::SeqCst);
+        });
+
+        self.monitor_handle = Some(handle);
     }
 
     fn test_at(&mut self) -> bool {
@@ -176,6 +769,14 @@ impl EC800KModem {
             }
         }
 
+        // 最近一次 GNSS 定位（若有）
+        if let Some(fix) = &self.last_fix {
+            info.insert(
+                "location".to_string(),
+                format!("{:.6},{:.6} ({}卫星)", fix.lat, fix.lon, fix.sats),
+            );
+        }
+
         info
     }
 
@@ -223,18 +824,140 @@ impl EC800KModem {
         status
     }
 
+    /// 打开 GNSS 接收机 (AT+QGPS=1)。已打开时模组返回 +CME ERROR: 504，同样视为就绪。
+    fn gnss_power_on(&mut self) -> bool {
+        let (success, resp) = self.send_at_command("AT+QGPS=1", AT_TIMEOUT);
+        success || resp.contains("504")
+    }
+
+    /// 轮询一次定位 (AT+QGPSLOC=0) 并解析为 GnssFix。
+    /// mode 0 返回 ddmm.mmmm(N/S)/dddmm.mmmm(E/W) 原始格式，配合 nmea_to_decimal 转十进制度；
+    /// 模组尚未定位时返回 +CME ERROR: 516，此处转为清晰的“暂无定位”错误。
+    fn get_location(&mut self) -> Result<GnssFix, String> {
+        let (_success, resp) = self.send_at_command("AT+QGPSLOC=0", AT_TIMEOUT);
+        if resp.contains("516") {
+            return Err("暂无定位 (no fix yet)".to_string());
+        }
+
+        // +QGPSLOC: <utc>,<lat>,<lon>,<hdop>,<alt>,<fix>,<cog>,<spkm>,<spkn>,<date>,<nsat>
+        let re = Regex::new(r"\+QGPSLOC:\s*([^\r\n]+)").unwrap();
+        let caps = re
+            .captures(&resp)
+            .ok_or_else(|| format!("无法解析定位响应: {}", resp))?;
+        let fields: Vec<&str> = caps[1].split(',').map(|s| s.trim()).collect();
+        if fields.len() < 11 {
+            return Err(format!("定位字段不足: {}", resp));
+        }
+
+        let lat = nmea_to_decimal(fields[1], 2).ok_or("纬度解析失败")?;
+        let lon = nmea_to_decimal(fields[2], 3).ok_or("经度解析失败")?;
+        let hdop: f64 = fields[3].parse().unwrap_or(0.0);
+        let alt: f64 = fields[4].parse().unwrap_or(0.0);
+        let sats: i32 = fields[10].parse().unwrap_or(0);
+
+        let fix = GnssFix {
+            lat,
+            lon,
+            alt,
+            sats,
+            hdop,
+            utc: fields[0].to_string(),
+        };
+        self.last_fix = Some(fix.clone());
+        Ok(fix)
+    }
+
+    /// 下载/读取升级包并做本地预校验：MD5 摘要 + 可选 Ed25519/HMAC 签名。
+    /// 任何一项不匹配都返回 Err，由 fota_upgrade 据此中止升级。
+    fn verify_package(&self, path_or_url: &str) -> Result<(), String> {
+        let cfg = match &self.verify {
+            Some(c) if c.is_active() => c,
+            _ => return Ok(()),
+        };
+
+        log("\n[预校验] 下载升级包并校验...");
+        let data = fetch_package(path_or_url)?;
+        log(&format!("📦 包大小: {} 字节", data.len()));
+
+        // (a)/(b) MD5 摘要校验
+        if let Some(expected) = &cfg.expected_md5 {
+            let actual = format!("{:x}", md5::compute(&data));
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                return Err(format!(
+                    "MD5 不匹配: 期望 {}, 实际 {}",
+                    expected.trim(),
+                    actual
+                ));
+            }
+            log(&format!("✅ MD5 校验通过: {}", actual));
+        }
+
+        // (c) 可选签名校验：算法由 --sig-alg 显式指定，不按密钥长度猜测
+        if let Some(key_path) = &cfg.key_file {
+            let key = fs::read(key_path).map_err(|e| format!("读取密钥失败: {}", e))?;
+            let sig_path = cfg
+                .sig_file
+                .as_ref()
+                .ok_or("提供了根密钥但缺少签名文件 (--sig)")?;
+            let sig_raw = fs::read(sig_path).map_err(|e| format!("读取签名失败: {}", e))?;
+            let alg = cfg
+                .sig_alg
+                .ok_or("提供了根密钥但未指定 --sig-alg (ed25519|hmac)")?;
+
+            if alg == SigAlg::Ed25519 {
+                let vk = VerifyingKey::from_bytes(
+                    key.as_slice()
+                        .try_into()
+                        .map_err(|_| "Ed25519 公钥长度应为 32 字节")?,
+                )
+                .map_err(|e| format!("无效的 Ed25519 公钥: {}", e))?;
+                let sig = Signature::from_slice(&sig_raw)
+                    .map_err(|e| format!("无效的 Ed25519 签名: {}", e))?;
+                vk.verify(&data, &sig)
+                    .map_err(|_| "Ed25519 签名校验失败".to_string())?;
+                log("✅ Ed25519 签名校验通过");
+            } else {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&key)
+                    .map_err(|e| format!("初始化 HMAC 失败: {}", e))?;
+                mac.update(&data);
+                mac.verify_slice(&sig_raw)
+                    .map_err(|_| "HMAC 签名校验失败".to_string())?;
+                log("✅ HMAC-SHA256 签名校验通过");
+            }
+        }
+
+        Ok(())
+    }
+
     fn fota_upgrade(&mut self, url: &str, auto_reset: i32, timeout: i32) -> (bool, String) {
         if url.len() > 700 {
             return (false, "URL长度超过700字符限制".to_string());
         }
 
+        // 预校验：包完整性/签名不过直接中止，省掉模组的一次下载周期
+        if let Err(reason) = self.verify_package(url) {
+            return (false, format!("升级包预校验失败: {}", reason));
+        }
+
+        // 停掉上一轮遗留的监听线程（重试场景）
+        self.stop_monitor.store(true, Ordering::SeqCst);
+        if let Some(h) = self.monitor_handle.take() {
+            let _ = h.join();
+        }
+
         // 重置状态
         {
-            let mut state = self.fota_state.lock().unwrap();
+            let (lock, _) = &*self.fota_state;
+            let mut state = lock.lock().unwrap();
             state.complete = false;
             state.result = -1;
+            state.progress = 0;
+            state.started_at = Instant::now();
+            state.last_update = Instant::now();
+            state.http_error = None;
+            state.downloading = true;
         }
-        *self.stop_monitor.lock().unwrap() = false;
+        self.stop_monitor.store(false, Ordering::SeqCst);
 
         println!("\n{}", "=".repeat(50));
         log("🔄 开始FOTA升级");
@@ -259,6 +982,25 @@ impl EC800KModem {
             log(&format!("📶 信号强度: {}", sig));
         }
 
+        // 2.4 记录升级发生的位置（尽力而为，无定位也继续升级）
+        log("\n[步骤2.4] 采集 GNSS 定位...");
+        if self.gnss_power_on() {
+            match self.get_location() {
+                Ok(fix) => log(&format!(
+                    "📍 位置: {:.6},{:.6} alt={:.1}m 卫星={}",
+                    fix.lat, fix.lon, fix.alt, fix.sats
+                )),
+                Err(e) => log(&format!("📍 {}", e)),
+            }
+        } else {
+            log("📍 GNSS 未就绪，跳过定位采集");
+        }
+
+        // 2.5 可选: 建立 MQTT 遥测通道（在监听线程启动前完成握手）
+        if self.mqtt.is_some() {
+            self.mqtt_connect();
+        }
+
         // 3. 发送FOTA升级指令
         log("\n[步骤3] 发送FOTA升级指令...");
         log(&format!("📎 URL: {}", url));
@@ -279,25 +1021,69 @@ impl EC800KModem {
         }
 
         log("✅ 指令发送成功，模组开始下载固件包...");
-        log("\n[步骤4] 等待升级进度上报...");
+
+        // 启动后台 URC 监听线程，驱动 FotaState
+        self.start_fota_monitor();
+        log("\n[步骤4] 后台监听已启动，等待升级进度上报...");
 
         (true, "FOTA升级已启动".to_string())
     }
 
-    fn wait_for_fota_complete(&self, max_wait: Duration) -> (bool, i32) {
-        log(&format!("\n⏳ 等待升级完成（最长{:?}）...", max_wait));
+    /// 重发 AT+QFOTADL 续传：模组会从已保存的偏移处继续下载。
+    /// 重置停滞计时，使续传后的无进度期不会立刻再次触发卡死判定。
+    fn resume_download(&mut self, url: &str, auto_reset: i32, timeout: i32) -> bool {
+        {
+            let (lock, _) = &*self.fota_state;
+            let mut st = lock.lock().unwrap();
+            st.http_error = None;
+            st.last_update = Instant::now();
+            st.downloading = true;
+        }
+        let cmd = format!("AT+QFOTADL=\"{}\",{},{}", url, auto_reset, timeout);
+        let (success, resp) = self.send_at_command(&cmd, Duration::from_secs(5));
+        if !success {
+            log(&format!("❌ 续传指令发送失败: {}", resp));
+        }
+        success
+    }
 
+    fn wait_for_fota_complete(&self, max_wait: Duration, idle_window: Duration) -> FotaWait {
+        log(&format!(
+            "\n⏳ 等待升级完成（最长{:?}，停滞窗口{:?}）...",
+            max_wait, idle_window
+        ));
+
+        let (lock, cvar) = &*self.fota_state;
         let start = Instant::now();
-        while start.elapsed() < max_wait {
-            let state = self.fota_state.lock().unwrap();
+        let mut state = lock.lock().unwrap();
+        loop {
             if state.complete {
-                return (state.result == 0, state.result);
+                return FotaWait::Done(state.result);
+            }
+            if let Some(err) = state.http_error.take() {
+                return FotaWait::DownloadError(err);
+            }
+            if start.elapsed() >= max_wait {
+                return FotaWait::Timeout;
+            }
+            // 距上次进度上报超过停滞窗口 → 判定卡死。
+            // 仅在下载阶段生效：HTTPEND 之后的烧写阶段本就不上报进度，不应判定卡死。
+            if state.downloading && state.last_update.elapsed() >= idle_window {
+                return FotaWait::Stall;
             }
-            drop(state);
-            thread::sleep(Duration::from_millis(500));
-        }
 
-        (false, -1) // 超时
+            // 只睡到下一个判定点：停滞窗口（仅下载阶段）与整体超时的较小剩余
+            let until_timeout = max_wait.saturating_sub(start.elapsed());
+            let wait = if state.downloading {
+                idle_window
+                    .saturating_sub(state.last_update.elapsed())
+                    .min(until_timeout)
+            } else {
+                until_timeout
+            };
+            let (guard, _timeout) = cvar.wait_timeout(state, wait).unwrap();
+            state = guard;
+        }
     }
 }
 
@@ -312,11 +1098,8 @@ fn list_serial_ports() {
             } else {
                 for port in ports {
                     println!("  {}", port.port_name);
-                    match port.port_type {
-                        serialport::SerialPortType::UsbPort(info) => {
-                            println!("    制造商: {}", info.manufacturer.unwrap_or_default());
-                        }
-                        _ => {}
+                    if let serialport::SerialPortType::UsbPort(info) = port.port_type {
+                        println!("    制造商: {}", info.manufacturer.unwrap_or_default());
                     }
                 }
             }
@@ -360,31 +1143,85 @@ fn run_basic_test(modem: &mut EC800KModem) -> bool {
 }
 
 fn run_fota_test(modem: &mut EC800KModem, url: &str, auto_reset: i32, timeout: i32) -> bool {
-    // 开始升级
-    let (success, msg) = modem.fota_upgrade(url, auto_reset, timeout);
-    if !success {
-        log(&format!("❌ {}", msg));
-        return false;
-    }
+    // 非零错误码结束时，复位并整体重试，直到成功或用尽 fota_retries
+    let max_attempts = modem.fota_retries + 1;
+    for attempt in 1..=max_attempts {
+        if attempt > 1 {
+            log(&format!(
+                "\n🔁 第 {}/{} 次 FOTA 重试（复位后重新下载）...",
+                attempt, max_attempts
+            ));
+            modem.recover();
+        }
+
+        // 开始升级
+        let (success, msg) = modem.fota_upgrade(url, auto_reset, timeout);
+        if !success {
+            log(&format!("❌ {}", msg));
+            return false;
+        }
 
-    // 等待完成 (简化版，不启动后台监听线程)
-    let (success, result_code) = modem.wait_for_fota_complete(Duration::from_secs(300));
+        // 等待完成 (由后台监听线程驱动 FotaState)，处理下载中断续传与卡死
+        let mut download_left = modem.download_retries;
+        let result_code = loop {
+            match modem.wait_for_fota_complete(Duration::from_secs(300), FOTA_IDLE_WINDOW) {
+                FotaWait::Done(code) => break code,
+                FotaWait::Timeout => {
+                    log("❌ 等待超时");
+                    return false;
+                }
+                FotaWait::Stall => {
+                    log("❌ 下载停滞，长时间无进度上报");
+                    return false;
+                }
+                FotaWait::DownloadError(err) => {
+                    if download_left == 0 {
+                        log(&format!("❌ 下载中断(错误码 {})，续传次数已用尽", err));
+                        break err;
+                    }
+                    download_left -= 1;
+                    log(&format!(
+                        "🔁 下载中断(错误码 {})，重发 QFOTADL 续传，剩余 {} 次...",
+                        err, download_left
+                    ));
+                    if !modem.resume_download(url, auto_reset, timeout) {
+                        return false;
+                    }
+                }
+            }
+        };
 
-    if success {
-        log("\n[步骤5] 验证新版本...");
-        thread::sleep(Duration::from_secs(5));
-        let new_version = modem.get_firmware_version();
-        if !new_version.is_empty() {
-            log(&format!("📌 新版本: {}", new_version));
+        if result_code == 0 {
+            log("\n[步骤5] 验证新版本...");
+            thread::sleep(Duration::from_secs(5));
+            let new_version = modem.get_firmware_version();
+            if !new_version.is_empty() {
+                log(&format!("📌 新版本: {}", new_version));
+            }
+            // 发布最终结果 {result, new_version}
+            modem.mqtt_publish(
+                "result",
+                &format!(
+                    "{{\"result\":{},\"new_version\":\"{}\"}}",
+                    result_code, new_version
+                ),
+            );
+            log("✅ FOTA升级成功!");
+            return true;
+        } else {
+            modem.mqtt_publish(
+                "result",
+                &format!("{{\"result\":{},\"new_version\":\"\"}}", result_code),
+            );
+            log(&format!("❌ 升级失败，错误码: {}", result_code));
+            if attempt >= max_attempts {
+                return false;
+            }
+            // 进入下一轮复位重试
         }
-        log("✅ FOTA升级成功!");
-    } else if result_code == -1 {
-        log("❌ 等待超时");
-    } else {
-        log(&format!("❌ 升级失败，错误码: {}", result_code));
     }
 
-    success
+    false
 }
 
 fn print_error_codes() {
@@ -420,12 +1257,20 @@ fn print_usage() {
     println!("  test                   - 基本测试（默认）");
     println!("  info                   - 显示错误码说明");
     println!("  version                - 仅查询固件版本");
-    println!("  fota URL [mode] [timeout]");
+    println!("  location               - 查询 GNSS 定位");
+    println!("  fota URL [mode] [timeout] [选项...]");
     println!("                         - FOTA升级");
     println!("                           mode: 0=手动重启, 1=自动重启");
+    println!("                           --mqtt mqtt://host:port/topic: 上报进度到MQTT");
+    println!("                           --md5 <hex>: 下载后比对包MD5");
+    println!("                           --sig-key <file>: 根密钥(Ed25519公钥/HMAC密钥)");
+    println!("                           --sig <file>: 对应的签名文件");
+    println!("                           --sig-alg ed25519|hmac: 签名算法(指定 --sig-key 时必填)");
+    println!("                           --retry <N>: 失败后复位并整体重试N次");
     println!("\n示例:");
     println!("  cargo run -- /dev/ttyUSB0 test");
     println!("  cargo run -- COM3 fota \"http://server/fota.bin\" 0 50");
+    println!("  cargo run -- COM3 fota \"http://server/fota.bin\" 0 50 --mqtt mqtt://broker:1883/fleet/dev1");
 }
 
 fn main() {
@@ -474,14 +1319,104 @@ fn main() {
                 println!("\n❌ 无法获取版本");
             }
         }
+        "location" => {
+            if modem.gnss_power_on() {
+                match modem.get_location() {
+                    Ok(fix) => {
+                        println!("\n📍 GNSS 定位:");
+                        println!("  纬度: {:.6}", fix.lat);
+                        println!("  经度: {:.6}", fix.lon);
+                        println!("  海拔: {:.1}m", fix.alt);
+                        println!("  HDOP: {}", fix.hdop);
+                        println!("  卫星数: {}", fix.sats);
+                        println!("  UTC: {}", fix.utc);
+                    }
+                    Err(e) => println!("\n❌ {}", e),
+                }
+            } else {
+                println!("\n❌ GNSS 开启失败");
+            }
+        }
         "fota" => {
-            if args.len() < 4 {
+            // 分离 --mqtt 等可选开关与位置参数
+            let mut mqtt_uri: Option<String> = None;
+            let mut fota_retries: u32 = 0;
+            let mut expected_md5: Option<String> = None;
+            let mut key_file: Option<String> = None;
+            let mut sig_file: Option<String> = None;
+            let mut sig_alg_arg: Option<String> = None;
+            let mut positional: Vec<String> = Vec::new();
+            let mut i = 3;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--mqtt" => {
+                        mqtt_uri = args.get(i + 1).cloned();
+                        i += 2;
+                    }
+                    "--md5" => {
+                        expected_md5 = args.get(i + 1).cloned();
+                        i += 2;
+                    }
+                    "--sig-key" => {
+                        key_file = args.get(i + 1).cloned();
+                        i += 2;
+                    }
+                    "--sig" => {
+                        sig_file = args.get(i + 1).cloned();
+                        i += 2;
+                    }
+                    "--sig-alg" => {
+                        sig_alg_arg = args.get(i + 1).cloned();
+                        i += 2;
+                    }
+                    "--retry" => {
+                        fota_retries = args.get(i + 1).and_then(|s| s.parse().ok()).unwrap_or(0);
+                        i += 2;
+                    }
+                    other => {
+                        positional.push(other.to_string());
+                        i += 1;
+                    }
+                }
+            }
+
+            if positional.is_empty() {
                 println!("❌ 请提供FOTA包URL");
-                println!("   用法: cargo run -- <串口> fota <URL> [mode] [timeout]");
+                println!("   用法: cargo run -- <串口> fota <URL> [mode] [timeout] [--mqtt mqtt://host:port/topic]");
             } else {
-                let url = &args[3];
-                let auto_reset = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(0);
-                let timeout = args.get(5).and_then(|s| s.parse().ok()).unwrap_or(50);
+                if let Some(uri) = mqtt_uri {
+                    match MqttReporter::from_uri(&uri) {
+                        Ok(r) => modem.mqtt = Some(r),
+                        Err(e) => println!("⚠️  忽略无效的 --mqtt 参数: {}", e),
+                    }
+                }
+                let sig_alg = match sig_alg_arg {
+                    Some(s) => match SigAlg::parse(&s) {
+                        Ok(a) => Some(a),
+                        Err(e) => {
+                            println!("❌ {}", e);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                if key_file.is_some() && sig_alg.is_none() {
+                    println!("❌ 指定 --sig-key 时必须同时提供 --sig-alg (ed25519|hmac)");
+                    return;
+                }
+                let verify = VerifyConfig {
+                    expected_md5,
+                    key_file,
+                    sig_file,
+                    sig_alg,
+                };
+                if verify.is_active() {
+                    modem.verify = Some(verify);
+                }
+                modem.fota_retries = fota_retries;
+                let url = &positional[0];
+                let auto_reset = positional.get(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+                let timeout = positional.get(2).and_then(|s| s.parse().ok()).unwrap_or(50);
                 run_fota_test(&mut modem, url, auto_reset, timeout);
             }
         }